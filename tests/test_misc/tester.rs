@@ -3,6 +3,7 @@
 use std::io::Write;
 use std::io::Read;
 
+use std::collections::HashMap;
 use std::str;
 use std::net;
 use std::net::ToSocketAddrs;
@@ -17,16 +18,36 @@ use httpbis::error::ErrorCode;
 use httpbis::solicit::header::*;
 use httpbis::solicit::frame::FrameIR;
 use httpbis::solicit::frame::settings::SettingsFrame;
+use httpbis::solicit::frame::settings::HttpSetting;
 use httpbis::solicit::frame::headers::HeadersFrame;
 use httpbis::solicit::frame::headers::HeadersFlag;
+use httpbis::solicit::frame::headers::StreamDependency;
+use httpbis::solicit::frame::priority::PriorityFrame;
+use httpbis::solicit::frame::ping::PingFrame;
 use httpbis::solicit::frame::data::DataFrame;
 use httpbis::solicit::frame::data::DataFlag;
 use httpbis::solicit::frame::goaway::GoawayFrame;
 use httpbis::solicit::frame::RawFrame;
 use httpbis::solicit::frame::rst_stream::RstStreamFrame;
+use httpbis::solicit::frame::window_update::WindowUpdateFrame;
+use httpbis::solicit::frame::continuation::ContinuationFrame;
+use httpbis::solicit::frame::continuation::ContinuationFlag;
 use httpbis::solicit::connection::HttpFrame;
 use httpbis::solicit::connection::HttpConnection;
 
+use futures::Future;
+use futures::future;
+use tokio_core::net::TcpStream as TokioTcpStream;
+use tokio_io::io as tokio_io;
+
+/// Default value of `SETTINGS_INITIAL_WINDOW_SIZE` before any SETTINGS frame
+/// changes it (RFC 7540, 6.5.2).
+const DEFAULT_INITIAL_WINDOW_SIZE: i32 = 65535;
+
+/// `SETTINGS_ENABLE_CONNECT_PROTOCOL`, RFC 8441 section 3. Not part of the base
+/// HTTP/2 settings registry, so it travels as an unknown/extension setting.
+const SETTINGS_ENABLE_CONNECT_PROTOCOL: u16 = 0x8;
+
 
 pub struct HttpServerTester(net::TcpListener);
 
@@ -54,6 +75,13 @@ impl HttpServerTester {
             tcp: self.0.accept().unwrap().0,
             conn: HttpConnection::new(),
             waiting_settings_ack: true,
+            initial_window_size: DEFAULT_INITIAL_WINDOW_SIZE,
+            conn_send_window: DEFAULT_INITIAL_WINDOW_SIZE,
+            stream_send_windows: HashMap::new(),
+            own_initial_window_size: DEFAULT_INITIAL_WINDOW_SIZE,
+            conn_recv_window: DEFAULT_INITIAL_WINDOW_SIZE,
+            stream_recv_windows: HashMap::new(),
+            window_violation: None,
         };
         debug!("accept connection.");
         r
@@ -66,6 +94,19 @@ pub struct HttpConnectionTester {
     tcp: net::TcpStream,
     conn: HttpConnection,
     waiting_settings_ack: bool,
+    // Flow control bookkeeping (RFC 7540, section 6.9).
+    // Peer's advertised `SETTINGS_INITIAL_WINDOW_SIZE` (from their SETTINGS frame):
+    // governs how much *we* may send, i.e. the default for `stream_send_windows`.
+    initial_window_size: i32,
+    conn_send_window: i32,
+    stream_send_windows: HashMap<StreamId, i32>,
+    // Our own advertised `SETTINGS_INITIAL_WINDOW_SIZE`: governs how much the peer may
+    // send *us*, i.e. the default for `stream_recv_windows`. Always the protocol
+    // default: nothing updates it, even if a test sends a SETTINGS frame with a custom value.
+    own_initial_window_size: i32,
+    conn_recv_window: i32,
+    stream_recv_windows: HashMap<StreamId, i32>,
+    window_violation: Option<String>,
 }
 
 impl HttpConnectionTester {
@@ -75,6 +116,13 @@ impl HttpConnectionTester {
                 .expect("connect"),
             conn: HttpConnection::new(),
             waiting_settings_ack: true,
+            initial_window_size: DEFAULT_INITIAL_WINDOW_SIZE,
+            conn_send_window: DEFAULT_INITIAL_WINDOW_SIZE,
+            stream_send_windows: HashMap::new(),
+            own_initial_window_size: DEFAULT_INITIAL_WINDOW_SIZE,
+            conn_recv_window: DEFAULT_INITIAL_WINDOW_SIZE,
+            stream_recv_windows: HashMap::new(),
+            window_violation: None,
         }
     }
 
@@ -101,30 +149,134 @@ impl HttpConnectionTester {
         self.send_frame(GoawayFrame::new(last_stream_id, ErrorCode::InadequateSecurity));
     }
 
-    pub fn send_headers(&mut self, stream_id: StreamId, headers: Headers, end: bool) {
+    /// Send a HEADERS frame. `priority`, when given, populates the frame's priority
+    /// section (`StreamDependency`'s stream id of `0` means "no dependency").
+    pub fn send_headers(&mut self, stream_id: StreamId, headers: Headers, end: bool, priority: Option<StreamDependency>) {
         let fragment = self.conn.encoder.encode(headers.0.iter().map(|h| (h.name(), h.value())));
         let mut headers_frame = HeadersFrame::new(fragment, stream_id);
         headers_frame.set_flag(HeadersFlag::EndHeaders);
         if end {
             headers_frame.set_flag(HeadersFlag::EndStream);
         }
+        if let Some(dependency) = priority {
+            headers_frame.set_priority(dependency);
+        }
         self.send_frame(headers_frame);
     }
 
+    /// Like `send_headers`, but splits the header block across a HEADERS frame and one or
+    /// more CONTINUATION frames, each carrying at most `max_fragment_len` bytes of the
+    /// encoded fragment. Useful for exercising the server's CONTINUATION reassembly.
+    pub fn send_headers_continued(
+        &mut self,
+        stream_id: StreamId,
+        headers: Headers,
+        end_stream: bool,
+        max_fragment_len: usize,
+    ) {
+        let fragment = self.conn.encoder.encode(headers.0.iter().map(|h| (h.name(), h.value())));
+        let mut chunks = fragment.chunks(max_fragment_len);
+
+        let first_chunk = chunks.next().unwrap_or(&[]);
+        let mut headers_frame = HeadersFrame::new(first_chunk.to_vec(), stream_id);
+        if end_stream {
+            headers_frame.set_flag(HeadersFlag::EndStream);
+        }
+        let remaining: Vec<&[u8]> = chunks.collect();
+        if remaining.is_empty() {
+            headers_frame.set_flag(HeadersFlag::EndHeaders);
+        }
+        self.send_frame(headers_frame);
+
+        let last = remaining.len().wrapping_sub(1);
+        for (i, chunk) in remaining.into_iter().enumerate() {
+            let mut continuation_frame = ContinuationFrame::new(chunk.to_vec(), stream_id);
+            if i == last {
+                continuation_frame.set_flag(ContinuationFlag::EndHeaders);
+            }
+            self.send_frame(continuation_frame);
+        }
+    }
+
     pub fn send_get(&mut self, stream_id: StreamId, path: &str) {
         let mut headers = Headers::new();
         headers.add(":method", "GET");
         headers.add(":path", path);
-        self.send_headers(stream_id, headers, true);
+        self.send_headers(stream_id, headers, true, None);
+    }
+
+    /// Send a standalone PRIORITY frame reprioritizing `stream_id` to depend on
+    /// `depends_on` (`0` = no dependency).
+    pub fn send_priority(&mut self, stream_id: StreamId, depends_on: StreamId, weight: u8, exclusive: bool) {
+        self.send_frame(PriorityFrame::new(stream_id, StreamDependency::new(depends_on, weight, exclusive)));
+    }
+
+    /// Send a PING, e.g. to check the server echoes the opaque payload back correctly.
+    pub fn send_ping(&mut self, opaque: [u8; 8]) {
+        self.send_frame(PingFrame::new(opaque));
+    }
+
+    /// Send an extended CONNECT (RFC 8441) request, e.g. to drive a WebSocket/tunnel
+    /// over HTTP/2. Leaves the stream open (no `EndStream`) so the caller can follow up
+    /// with bidirectional DATA once the 200 response arrives.
+    pub fn send_connect(&mut self, stream_id: StreamId, authority: &str, protocol: &str) {
+        let mut headers = Headers::new();
+        headers.add(":method", "CONNECT");
+        headers.add(":protocol", protocol);
+        headers.add(":scheme", "https");
+        headers.add(":path", "/");
+        headers.add(":authority", authority);
+        self.send_headers(stream_id, headers, false, None);
     }
 
     pub fn send_data(&mut self, stream_id: StreamId, data: &[u8], end: bool) {
+        let len = data.len() as i32;
+        let stream_window = *self.stream_send_windows.entry(stream_id).or_insert(self.initial_window_size);
+        assert!(
+            len <= stream_window,
+            "stream {} send window exceeded: tried to send {} bytes, window is {}",
+            stream_id, len, stream_window);
+        assert!(
+            len <= self.conn_send_window,
+            "connection send window exceeded: tried to send {} bytes, window is {}",
+            len, self.conn_send_window);
+
         let mut data_frame = DataFrame::new(stream_id);
         data_frame.data = Bytes::from(data);
         if end {
             data_frame.set_flag(DataFlag::EndStream);
         }
         self.send_frame(data_frame);
+
+        *self.stream_send_windows.get_mut(&stream_id).unwrap() -= len;
+        self.conn_send_window -= len;
+    }
+
+    /// Send a `WINDOW_UPDATE`, growing the window we grant the peer to send us data on
+    /// `stream_id` (or the connection-level window, when `stream_id == 0`).
+    pub fn send_window_update(&mut self, stream_id: StreamId, increment: u32) {
+        self.send_frame(WindowUpdateFrame::for_stream(stream_id, increment));
+
+        if stream_id == 0 {
+            self.conn_recv_window += increment as i32;
+        } else {
+            let w = self.stream_recv_windows.entry(stream_id).or_insert(self.own_initial_window_size);
+            *w += increment as i32;
+        }
+    }
+
+    fn track_data_received(&mut self, stream_id: StreamId, len: usize) {
+        let len = len as i32;
+        self.conn_recv_window -= len;
+        let stream_window = self.stream_recv_windows.entry(stream_id).or_insert(self.own_initial_window_size);
+        *stream_window -= len;
+
+        if self.window_violation.is_none() && (self.conn_recv_window < 0 || *stream_window < 0) {
+            self.window_violation = Some(format!(
+                "server sent more DATA on stream {} than the advertised window allowed \
+                 (connection window {}, stream window {})",
+                stream_id, self.conn_recv_window, *stream_window));
+        }
     }
 
     pub fn send_rst(&mut self, stream_id: StreamId, error_code: ErrorCode) {
@@ -152,10 +304,42 @@ impl HttpConnectionTester {
                 }
                 continue;
             }
+            if let HttpFrame::Data(ref data) = frame {
+                self.track_data_received(data.stream_id, data.data.len());
+            }
+            if let HttpFrame::WindowUpdate(ref window_update) = frame {
+                self.track_window_update_received(window_update);
+            }
             return frame;
         }
     }
 
+    fn track_window_update_received(&mut self, window_update: &WindowUpdateFrame) {
+        let increment = window_update.increment as i32;
+        if window_update.stream_id == 0 {
+            self.conn_send_window += increment;
+        } else {
+            let w = self.stream_send_windows.entry(window_update.stream_id).or_insert(self.initial_window_size);
+            *w += increment;
+        }
+    }
+
+    /// Receive a `WINDOW_UPDATE` frame, failing the test if a different frame arrives.
+    pub fn recv_frame_window_update(&mut self) -> WindowUpdateFrame {
+        match self.recv_frame() {
+            HttpFrame::WindowUpdate(window_update) => window_update,
+            f => panic!("expecting WINDOW_UPDATE, got: {:?}", f),
+        }
+    }
+
+    /// Fail the test if the server has ever sent more DATA on the connection or on any
+    /// stream than the window we advertised to it permitted.
+    pub fn assert_server_respected_window(&self) {
+        if let Some(ref violation) = self.window_violation {
+            panic!("{}", violation);
+        }
+    }
+
     pub fn recv_frame_settings(&mut self) -> SettingsFrame {
         match self.fn_recv_frame_no_check_ack() {
             HttpFrame::Settings(settings) => settings,
@@ -166,6 +350,11 @@ impl HttpConnectionTester {
     pub fn recv_frame_settings_set(&mut self) -> SettingsFrame {
         let settings = self.recv_frame_settings();
         assert!(!settings.is_ack());
+        for setting in &settings.settings {
+            if let HttpSetting::InitialWindowSize(size) = *setting {
+                self.initial_window_size = size as i32;
+            }
+        }
         settings
     }
 
@@ -183,6 +372,19 @@ impl HttpConnectionTester {
         self.recv_message(stream_id)
     }
 
+    /// Start building a request on `stream_id` via the fluent `RequestTester` API, e.g.
+    /// `tester.request(1).method("POST").path("/echo").body(b"hi").send().expect_status(200)`.
+    pub fn request(&mut self, stream_id: StreamId) -> RequestTester {
+        RequestTester {
+            tester: self,
+            stream_id,
+            method: "GET".to_owned(),
+            path: "/".to_owned(),
+            headers: Headers::new(),
+            body: None,
+        }
+    }
+
     // Perform handshape, but do not wait for ACK of my SETTINGS
     // Useful, because ACK may come e.g. after first request HEADERS
     pub fn settings_xchg_but_ack(&mut self) {
@@ -196,6 +398,27 @@ impl HttpConnectionTester {
         self.recv_frame_settings_ack();
     }
 
+    /// Like `settings_xchg`, but our SETTINGS frame additionally carries
+    /// `SETTINGS_ENABLE_CONNECT_PROTOCOL = 1`, advertising extended CONNECT support from this end.
+    pub fn settings_xchg_with_connect_protocol(&mut self) {
+        let mut settings = SettingsFrame::new();
+        settings.settings.push(HttpSetting::Unknown(SETTINGS_ENABLE_CONNECT_PROTOCOL, 1));
+        self.send_frame(settings);
+        self.recv_frame_settings_set();
+        self.send_frame(SettingsFrame::new_ack());
+        self.recv_frame_settings_ack();
+    }
+
+    /// Assert that the server's SETTINGS advertised `SETTINGS_ENABLE_CONNECT_PROTOCOL`.
+    pub fn recv_frame_settings_check_connect_protocol(&mut self) {
+        let settings = self.recv_frame_settings_set();
+        let enabled = settings.settings.iter().any(|s| match *s {
+            HttpSetting::Unknown(SETTINGS_ENABLE_CONNECT_PROTOCOL, value) => value == 1,
+            _ => false,
+        });
+        assert!(enabled, "server did not advertise SETTINGS_ENABLE_CONNECT_PROTOCOL");
+    }
+
     pub fn recv_rst_frame(&mut self) -> RstStreamFrame {
         match self.recv_frame() {
             HttpFrame::RstStream(rst) => rst,
@@ -209,6 +432,63 @@ impl HttpConnectionTester {
         assert_eq!(error_code, frame.error_code());
     }
 
+    /// Capture a server-initiated PING (not an ack of one of ours), to verify
+    /// keepalive-interval behavior.
+    pub fn recv_frame_ping(&mut self) -> PingFrame {
+        match self.recv_frame() {
+            HttpFrame::Ping(ping) => {
+                assert!(!ping.is_ack(), "expecting a server-initiated PING, got a PING ack");
+                ping
+            }
+            f => panic!("expecting PING, got: {:?}", f),
+        }
+    }
+
+    /// Read frames until a PING ack arrives, and assert its opaque payload matches what
+    /// we sent. The server must not ack its own PINGs, so any PING it sends before the
+    /// ack is simply skipped here.
+    pub fn recv_ping_ack_check(&mut self, expected: [u8; 8]) {
+        loop {
+            match self.recv_frame() {
+                HttpFrame::Ping(ping) => {
+                    if ping.is_ack() {
+                        assert_eq!(&expected[..], ping.opaque_data());
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn recv_frame_priority(&mut self) -> PriorityFrame {
+        match self.recv_frame() {
+            HttpFrame::Priority(priority) => priority,
+            f => panic!("expecting PRIORITY, got: {:?}", f),
+        }
+    }
+
+    pub fn recv_frame_priority_check(
+        &mut self,
+        stream_id: StreamId,
+        depends_on: StreamId,
+        weight: u8,
+        exclusive: bool,
+    ) {
+        let priority = self.recv_frame_priority();
+        assert_eq!(stream_id, priority.stream_id);
+        assert_eq!(depends_on, priority.dependency.stream_id);
+        assert_eq!(weight, priority.dependency.weight);
+        assert_eq!(exclusive, priority.dependency.is_exclusive);
+    }
+
+    /// Assert that `stream_id` was reset with `PROTOCOL_ERROR`, as RFC 7540 5.3.1 requires
+    /// when a PRIORITY (or a HEADERS frame's priority section) makes a stream depend on
+    /// itself.
+    pub fn recv_rst_frame_check_self_dependency(&mut self, stream_id: StreamId) {
+        self.recv_rst_frame_check(stream_id, ErrorCode::ProtocolError);
+    }
+
     pub fn recv_frame_headers(&mut self) -> HeadersFrame {
         match self.recv_frame() {
             HttpFrame::Headers(headers) => headers,
@@ -224,13 +504,35 @@ impl HttpConnectionTester {
     }
 
     pub fn recv_frame_headers_check(&mut self, stream_id: StreamId, end: bool) -> Headers {
-        let headers = self.recv_frame_headers();
-        assert_eq!(stream_id, headers.stream_id);
-        assert_eq!(end, headers.is_end_of_stream());
-        let headers = self.conn.decoder.decode(headers.header_fragment()).expect("decode");
+        let headers_frame = self.recv_frame_headers();
+        assert_eq!(stream_id, headers_frame.stream_id);
+        assert_eq!(end, headers_frame.is_end_of_stream());
+        let fragment = self.recv_header_fragment_continued(stream_id, headers_frame);
+        let headers = self.conn.decoder.decode(&fragment).expect("decode");
         Headers(headers.into_iter().map(|(n, v)| Header::new(n, v)).collect())
     }
 
+    /// Accumulate the header block fragment of `headers_frame`, reading CONTINUATION
+    /// frames off the wire for as long as `EndHeaders` is not set. Any frame on a
+    /// different stream while a header block is in progress is a protocol error.
+    fn recv_header_fragment_continued(&mut self, stream_id: StreamId, headers_frame: HeadersFrame) -> Vec<u8> {
+        let mut fragment = headers_frame.header_fragment().to_vec();
+        let mut end_headers = headers_frame.is_end_of_headers();
+        while !end_headers {
+            match self.fn_recv_frame_no_check_ack() {
+                HttpFrame::Continuation(continuation) => {
+                    assert_eq!(
+                        stream_id, continuation.stream_id,
+                        "CONTINUATION arrived on a different stream while assembling headers");
+                    end_headers = continuation.is_end_of_headers();
+                    fragment.extend_from_slice(continuation.header_fragment());
+                }
+                f => panic!("expecting CONTINUATION, got: {:?}", f),
+            }
+        }
+        fragment
+    }
+
     pub fn recv_frame_data_check(&mut self, stream_id: StreamId, end: bool) -> Vec<u8> {
         let data = self.recv_frame_data();
         assert_eq!(stream_id, data.stream_id);
@@ -251,7 +553,8 @@ impl HttpConnectionTester {
             let end_of_stream = match frame {
                 HttpFrame::Headers(headers_frame) => {
                     let end_of_stream = headers_frame.is_end_of_stream();
-                    let headers = self.conn.decoder.decode(headers_frame.header_fragment()).expect("decode");
+                    let fragment = self.recv_header_fragment_continued(stream_id, headers_frame);
+                    let headers = self.conn.decoder.decode(&fragment).expect("decode");
                     let headers = Headers(headers.into_iter().map(|(n, v)| Header::new(n, v)).collect());
                     r.headers.extend(headers);
                     end_of_stream
@@ -268,4 +571,292 @@ impl HttpConnectionTester {
             }
         }
     }
+}
+
+/// Async counterpart of `HttpConnectionTester`, built on the same frame codec as
+/// `solicit_async` but driven through a tokio `TcpStream` instead of blocking I/O.
+///
+/// Each operation consumes `self` and returns a future resolving to the tester again, so
+/// that callers can chain steps (`tester.send_frame(f).and_then(|t| t.recv_frame())`) and
+/// interleave them with other futures to test behaviors the sync harness cannot express,
+/// e.g. a server reacting to an inbound frame while a response is mid-flight.
+pub struct AsyncHttpConnectionTester {
+    tcp: TokioTcpStream,
+    conn: HttpConnection,
+    waiting_settings_ack: bool,
+}
+
+impl AsyncHttpConnectionTester {
+    pub fn new(tcp: TokioTcpStream) -> AsyncHttpConnectionTester {
+        AsyncHttpConnectionTester {
+            tcp,
+            conn: HttpConnection::new(),
+            waiting_settings_ack: true,
+        }
+    }
+
+    pub fn send_frame<F>(self, frame: F) -> Box<Future<Item = AsyncHttpConnectionTester, Error = httpbis::Error>>
+    where
+        F: FrameIR,
+    {
+        let AsyncHttpConnectionTester { tcp, conn, waiting_settings_ack } = self;
+        Box::new(
+            tokio_io::write_all(tcp, frame.serialize_into_vec())
+                .map(move |(tcp, _)| AsyncHttpConnectionTester { tcp, conn, waiting_settings_ack })
+                .map_err(httpbis::Error::from))
+    }
+
+    fn fn_recv_frame_no_check_ack(self) -> Box<Future<Item = (AsyncHttpConnectionTester, HttpFrame), Error = httpbis::Error>> {
+        let AsyncHttpConnectionTester { tcp, conn, waiting_settings_ack } = self;
+        Box::new(
+            httpbis::solicit_async::recv_raw_frame_async(tcp)
+                .map_err(httpbis::Error::from)
+                .map(move |(tcp, raw_frame)| {
+                    let frame = HttpFrame::from_raw(&raw_frame).expect("parse frame");
+                    debug!("received frame: {:?}", frame);
+                    let tester = AsyncHttpConnectionTester { tcp, conn, waiting_settings_ack };
+                    (tester, frame)
+                }))
+    }
+
+    /// Async counterpart of `HttpConnectionTester::recv_header_fragment_continued`:
+    /// accumulate `fragment` with CONTINUATION frames until `EndHeaders` is set.
+    fn recv_header_fragment_continued(
+        self,
+        stream_id: StreamId,
+        mut fragment: Vec<u8>,
+        end_headers: bool,
+    ) -> Box<Future<Item = (AsyncHttpConnectionTester, Vec<u8>), Error = httpbis::Error>> {
+        if end_headers {
+            return Box::new(future::ok((self, fragment)));
+        }
+        Box::new(self.fn_recv_frame_no_check_ack().and_then(move |(tester, frame)| {
+            match frame {
+                HttpFrame::Continuation(continuation) => {
+                    assert_eq!(
+                        stream_id, continuation.stream_id,
+                        "CONTINUATION arrived on a different stream while assembling headers");
+                    let end_headers = continuation.is_end_of_headers();
+                    fragment.extend_from_slice(continuation.header_fragment());
+                    tester.recv_header_fragment_continued(stream_id, fragment, end_headers)
+                }
+                f => panic!("expecting CONTINUATION, got: {:?}", f),
+            }
+        }))
+    }
+
+    pub fn recv_frame(self) -> Box<Future<Item = (AsyncHttpConnectionTester, HttpFrame), Error = httpbis::Error>> {
+        Box::new(self.fn_recv_frame_no_check_ack().and_then(|(mut tester, frame)| {
+            if let HttpFrame::Settings(ref f) = frame {
+                if tester.waiting_settings_ack && f.is_ack() {
+                    tester.waiting_settings_ack = false;
+                    return tester.recv_frame();
+                }
+                return tester.recv_frame();
+            }
+            Box::new(future::ok((tester, frame)))
+        }))
+    }
+
+    pub fn settings_xchg(self) -> Box<Future<Item = AsyncHttpConnectionTester, Error = httpbis::Error>> {
+        Box::new(
+            self.send_frame(SettingsFrame::new())
+                .and_then(|tester| tester.fn_recv_frame_no_check_ack())
+                .and_then(|(tester, frame)| match frame {
+                    HttpFrame::Settings(settings) => {
+                        assert!(!settings.is_ack());
+                        tester.send_frame(SettingsFrame::new_ack())
+                    }
+                    f => panic!("expecting SETTINGS, got: {:?}", f),
+                })
+                .and_then(|mut tester| {
+                    tester.waiting_settings_ack = false;
+                    tester.fn_recv_frame_no_check_ack()
+                })
+                .and_then(|(tester, frame)| match frame {
+                    HttpFrame::Settings(settings) => {
+                        assert!(settings.is_ack());
+                        future::ok(tester)
+                    }
+                    f => panic!("expecting SETTINGS ack, got: {:?}", f),
+                }))
+    }
+
+    pub fn recv_message(
+        self,
+        stream_id: StreamId,
+    ) -> Box<Future<Item = (AsyncHttpConnectionTester, SimpleHttpMessage), Error = httpbis::Error>> {
+        fn step(
+            tester: AsyncHttpConnectionTester,
+            stream_id: StreamId,
+            mut message: SimpleHttpMessage,
+        ) -> Box<Future<Item = (AsyncHttpConnectionTester, SimpleHttpMessage), Error = httpbis::Error>> {
+            Box::new(tester.recv_frame().and_then(move |(tester, frame)| {
+                assert_eq!(stream_id, frame.get_stream_id());
+                match frame {
+                    HttpFrame::Headers(headers_frame) => {
+                        let end_of_stream = headers_frame.is_end_of_stream();
+                        let end_headers = headers_frame.is_end_of_headers();
+                        let fragment = headers_frame.header_fragment().to_vec();
+                        Box::new(
+                            tester.recv_header_fragment_continued(stream_id, fragment, end_headers)
+                                .and_then(move |(tester, fragment)| {
+                                    let headers = tester.conn.decoder.decode(&fragment).expect("decode");
+                                    let headers = Headers(headers.into_iter().map(|(n, v)| Header::new(n, v)).collect());
+                                    message.headers.extend(headers);
+                                    if end_of_stream {
+                                        Box::new(future::ok((tester, message)))
+                                            as Box<Future<Item = (AsyncHttpConnectionTester, SimpleHttpMessage), Error = httpbis::Error>>
+                                    } else {
+                                        step(tester, stream_id, message)
+                                    }
+                                })
+                        ) as Box<Future<Item = (AsyncHttpConnectionTester, SimpleHttpMessage), Error = httpbis::Error>>
+                    }
+                    HttpFrame::Data(data_frame) => {
+                        let end_of_stream = data_frame.is_end_of_stream();
+                        bytes_extend_with(&mut message.body, data_frame.data);
+                        if end_of_stream {
+                            Box::new(future::ok((tester, message)))
+                        } else {
+                            step(tester, stream_id, message)
+                        }
+                    }
+                    frame => panic!("expecting HEADERS or DATA, got: {:?}", frame),
+                }
+            }))
+        }
+
+        step(self, stream_id, SimpleHttpMessage::default())
+    }
+}
+
+/// Fluent request builder returned by `HttpConnectionTester::request`, inspired by
+/// actix-web's `TestRequest`. Chain setters, then `.send()` to get a `ResponseTester`.
+pub struct RequestTester<'a> {
+    tester: &'a mut HttpConnectionTester,
+    stream_id: StreamId,
+    method: String,
+    path: String,
+    headers: Headers,
+    body: Option<Vec<u8>>,
+}
+
+impl<'a> RequestTester<'a> {
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = method.to_owned();
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.add(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = Some(body.to_vec());
+        self
+    }
+
+    pub fn send(self) -> ResponseTester<'a> {
+        let expects_100_continue = self.headers.0.iter()
+            .any(|h| h.name() == b"expect" && h.value().eq_ignore_ascii_case(b"100-continue"));
+
+        let mut headers = Headers::new();
+        headers.add(":method", &self.method);
+        headers.add(":path", &self.path);
+        headers.extend(self.headers);
+
+        // RFC 7540 8.2.3 / RFC 7231 5.1.1: with `expect: 100-continue`, the body must be
+        // withheld until the interim 100 response arrives, so buffer it instead of
+        // sending it with the request headers.
+        let defer_body = expects_100_continue && self.body.is_some();
+        let end_stream = if defer_body { false } else { self.body.is_none() };
+        self.tester.send_headers(self.stream_id, headers, end_stream, None);
+
+        let pending_body = if defer_body {
+            self.body
+        } else {
+            if let Some(body) = self.body {
+                self.tester.send_data(self.stream_id, &body, true);
+            }
+            None
+        };
+
+        ResponseTester {
+            tester: self.tester,
+            stream_id: self.stream_id,
+            message: None,
+            pending_body,
+        }
+    }
+}
+
+/// Assertions on the response to a request sent through `RequestTester::send`. Assertions
+/// can be chained; the response message is fetched (and cached) on first use.
+pub struct ResponseTester<'a> {
+    tester: &'a mut HttpConnectionTester,
+    stream_id: StreamId,
+    message: Option<SimpleHttpMessage>,
+    // Body buffered by `RequestTester::send` when `expect: 100-continue` was set; sent
+    // once `expect_100_continue` observes the interim response.
+    pending_body: Option<Vec<u8>>,
+}
+
+impl<'a> ResponseTester<'a> {
+    fn header<'s>(headers: &'s Headers, name: &str) -> &'s [u8] {
+        headers.0.iter()
+            .find(|h| h.name() == name.as_bytes())
+            .unwrap_or_else(|| panic!("header {} not found in response", name))
+            .value()
+    }
+
+    fn message(&mut self) -> &SimpleHttpMessage {
+        if self.message.is_none() {
+            assert!(
+                self.pending_body.is_none(),
+                "request sent `expect: 100-continue`; call expect_100_continue() before \
+                 asserting on the final response, so the buffered body gets sent");
+            let stream_id = self.stream_id;
+            self.message = Some(self.tester.recv_message(stream_id));
+        }
+        self.message.as_ref().unwrap()
+    }
+
+    /// Assert an interim `100 Continue` HEADERS frame arrives before the final response,
+    /// for testing the `expect: 100-continue` flow, then release the body that `send()`
+    /// buffered so it follows on the wire.
+    pub fn expect_100_continue(mut self) -> Self {
+        let stream_id = self.stream_id;
+        let headers = self.tester.recv_frame_headers_check(stream_id, false);
+        assert_eq!(b"100", Self::header(&headers, ":status"));
+        if let Some(body) = self.pending_body.take() {
+            self.tester.send_data(stream_id, &body, true);
+        }
+        self
+    }
+
+    pub fn expect_status(mut self, status: u32) -> Self {
+        let expected = status.to_string();
+        let message = self.message();
+        assert_eq!(expected.as_bytes(), Self::header(&message.headers, ":status"));
+        self
+    }
+
+    pub fn expect_header(mut self, name: &str, value: &str) -> Self {
+        let message = self.message();
+        assert_eq!(value.as_bytes(), Self::header(&message.headers, name));
+        self
+    }
+
+    pub fn expect_body(mut self, body: &[u8]) -> Self {
+        let message = self.message();
+        assert_eq!(body, &message.body[..]);
+        self
+    }
 }
\ No newline at end of file